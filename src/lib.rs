@@ -1,12 +1,13 @@
 #![cfg_attr(not(any(test, feature = "export-abi")), no_main)]
 extern crate alloc;
 
-use alloc::vec::Vec;
+use alloc::{string::String, vec::Vec};
 use stylus_sdk::{
     alloy_primitives::{Address, U256},
     alloy_sol_types::sol,
+    call::Call,
     prelude::*,
-    storage::{StorageAddress, StorageMap},
+    storage::{StorageAddress, StorageBool, StorageMap, StorageU256, StorageU8, StorageVec},
 };
 
 //*//////////////////////////////////////////////////////////////////////////
@@ -15,14 +16,26 @@ use stylus_sdk::{
 
 // Minimal interface for the Supra VRF Router Contract
 // The `generateRequest` function is used to request randomness from Supra VRF
+// The second overload accepts a `client_seed` so the caller can mix in its own
+// entropy alongside the router's verifiable randomness
 sol_interface! {
     interface ISupraRouterContract {
         function generateRequest(string memory function_sig, uint8 rng_count, uint256 num_confirmations, address client_wallet_address) external returns(uint256);
+        function generateRequest(string memory function_sig, uint8 rng_count, uint256 num_confirmations, address client_wallet_address, uint256 client_seed) external returns(uint256);
     }
 
     interface IErc20 {
         function mint(address account, uint256 value) external;
     }
+
+    // Supra's Deposit Contract pre-pays callback gas for whitelisted client
+    // contracts, funded through a registered client wallet
+    interface ISupraDepositContract {
+        function depositFundClient() external payable;
+        function addContractToWhitelist(address contract_address) external;
+        function removeContractFromWhitelist(address contract_address) external;
+        function checkClientFund(address client_address) external view returns(uint256);
+    }
 }
 
 sol! {
@@ -35,12 +48,49 @@ sol! {
     // Thrown when a fulfillment is received from a non-Supra router
     #[derive(Debug)]
     error OnlySupraRouter();
+    // Thrown when a fulfillment is received for a nonce that was never
+    // requested, or that was already fulfilled
+    #[derive(Debug)]
+    error UnknownRequest();
+    // Thrown when a non-owner calls an owner-gated function
+    #[derive(Debug)]
+    error OnlyOwner();
+    // Thrown when `draw_winner` is called with no entrants in the round
+    #[derive(Debug)]
+    error NoEntrants();
+    // Thrown when `draw_winner` is called while a draw for the current
+    // round is already in flight
+    #[derive(Debug)]
+    error DrawInProgress();
+    // Thrown when `set_trait_cardinality` is given a zero cardinality, which
+    // would make that trait slot divide by zero on every roll
+    #[derive(Debug)]
+    error InvalidTraitCardinality();
+    // Thrown when `owner_of` is queried for a token id that was never minted
+    #[derive(Debug)]
+    error NonexistentToken();
+    // Thrown when `set_trait_cardinality` is given more slots than fit in the
+    // router's `uint8` rng_count
+    #[derive(Debug)]
+    error TooManyTraits();
+    // Thrown when a call into the Supra Deposit Contract fails
+    #[derive(Debug)]
+    error SupraCallFailed();
+    // Thrown when a fulfillment would mint past the configured supply cap
+    #[derive(Debug)]
+    error SupplyCapExceeded();
+    // Thrown when `set_mint_range` is given a min that is greater than max
+    #[derive(Debug)]
+    error InvalidMintRange();
 }
 
 // Custom events
 sol! {
     event MintRequested(uint256 indexed nonce, address indexed to);
     event Minted(uint256 indexed nonce, address indexed to, uint256 amount);
+    event TraitsAssigned(uint256 indexed token_id, uint256[] traits);
+    event Transfer(address indexed from, address indexed to, uint256 indexed token_id);
+    event WinnerSelected(uint256 indexed round_id, address indexed winner, uint256 amount);
 }
 
 #[derive(SolidityError, Debug)]
@@ -49,6 +99,16 @@ enum Error {
     RandomnessRequestFailed(RandomnessRequestFailed),
     OnlySupraRouter(OnlySupraRouter),
     MintFailed(MintFailed),
+    UnknownRequest(UnknownRequest),
+    OnlyOwner(OnlyOwner),
+    NoEntrants(NoEntrants),
+    DrawInProgress(DrawInProgress),
+    InvalidTraitCardinality(InvalidTraitCardinality),
+    NonexistentToken(NonexistentToken),
+    TooManyTraits(TooManyTraits),
+    SupraCallFailed(SupraCallFailed),
+    SupplyCapExceeded(SupplyCapExceeded),
+    InvalidMintRange(InvalidMintRange),
 }
 
 //*//////////////////////////////////////////////////////////////////////////
@@ -58,10 +118,44 @@ enum Error {
 #[entrypoint]
 #[storage]
 struct LotteryToken {
+    owner: StorageAddress,
     rng_token: StorageAddress,
     subscription_manager: StorageAddress,
     supra_router: StorageAddress,
+    supra_deposit: StorageAddress,
     mint_address: StorageMap<U256, StorageAddress>,
+    // Tracks which requested nonces are still awaiting fulfillment, so a
+    // fulfillment can't be replayed or forged for an unrequested nonce
+    request_pending: StorageMap<U256, StorageBool>,
+    // Number of random words requested per VRF call; owner-tunable so a
+    // single request can fan out into a batch mint for its receiver
+    rng_count: StorageU8,
+    // Owner-tunable bounds for each random mint, and an overall tokenomics cap
+    min_mint: StorageU256,
+    max_mint: StorageU256,
+    total_minted: StorageU256,
+    supply_cap: StorageU256,
+    // ERC-721 trait randomization subsystem: tracks NFT ownership, the
+    // cardinality of each trait slot, and the rolled trait values per token
+    next_token_id: StorageU256,
+    nft_owner: StorageMap<U256, StorageAddress>,
+    trait_cardinality: StorageVec<StorageU256>,
+    token_traits: StorageMap<U256, StorageMap<u8, U256>>,
+    // Number of traits actually rolled for each token, fixed at mint time so
+    // a later `set_trait_cardinality` change can't desync `get_traits`
+    token_trait_count: StorageMap<U256, StorageU8>,
+    // Maps a pending trait-roll nonce back to the token id it belongs to
+    trait_request_token: StorageMap<U256, U256>,
+    // Raffle subsystem: one VRF draw picks a single winner from the
+    // entrants of the current round
+    round_id: StorageU256,
+    raffle_prize: StorageU256,
+    entrants: StorageMap<U256, StorageVec<StorageAddress>>,
+    // Maps a pending draw nonce back to the round it was drawn for
+    raffle_request_round: StorageMap<U256, U256>,
+    // Tracks which rounds have a VRF draw already in flight, so a round can
+    // never have two live requests racing to mint its prize
+    raffle_draw_pending: StorageMap<U256, StorageBool>,
 }
 
 #[public]
@@ -72,19 +166,173 @@ impl LotteryToken {
         rng_token: Address,
         subscription_manager: Address,
         supra_router: Address,
+        supra_deposit: Address,
     ) -> Result<(), Error> {
-        self._init(rng_token, subscription_manager, supra_router)
+        self._init(rng_token, subscription_manager, supra_router, supra_deposit)
     }
 
     pub fn mint_to(&mut self, to: Address) -> Result<(), Error> {
         self._mint_to(to)
     }
 
+    // Same as `mint_to`, but mixes a caller-supplied `client_seed` into the
+    // randomness request via the router's seeded `generateRequest` overload
+    pub fn mint_to_with_seed(&mut self, to: Address, client_seed: U256) -> Result<(), Error> {
+        self._mint_to_with_seed(to, client_seed)
+    }
+
     // Callback function from Supra VRF, called when the randomness is fulfilled
     // This is not meant to be called by users
     pub fn mint_random_amount(&mut self, nonce: U256, rng_list: Vec<U256>) -> Result<(), Error> {
         self._mint_random_amount(nonce, rng_list)
     }
+
+    // Owner-only: sets how many random words the router should return per
+    // request, allowing a single VRF callback to mint several amounts
+    pub fn set_rng_count(&mut self, rng_count: u8) -> Result<(), Error> {
+        self._only_owner()?;
+        self.rng_count.set(rng_count);
+        Ok(())
+    }
+
+    // Owner-only: configures the number of possible values for each trait
+    // slot, e.g. `[4, 6, 10]` for three traits with those cardinalities
+    pub fn set_trait_cardinality(&mut self, cardinality: Vec<U256>) -> Result<(), Error> {
+        self._only_owner()?;
+
+        // A zero cardinality would divide by zero on every future roll of
+        // that trait slot, permanently stranding the nonce that hits it
+        if cardinality.iter().any(|value| value.is_zero()) {
+            return Err(Error::InvalidTraitCardinality(InvalidTraitCardinality {}));
+        }
+        // The slot count is passed to the router as a `uint8` rng_count, so
+        // more than 255 slots would silently truncate instead of requesting
+        // enough random words
+        if cardinality.len() > u8::MAX as usize {
+            return Err(Error::TooManyTraits(TooManyTraits {}));
+        }
+
+        while self.trait_cardinality.pop().is_some() {}
+        for value in cardinality {
+            self.trait_cardinality.push(value);
+        }
+
+        Ok(())
+    }
+
+    // Mints an NFT to `to` and requests one random word per configured trait
+    // slot; traits land once the Supra router calls back into `fulfill_traits`
+    pub fn mint_nft_with_traits(&mut self, to: Address) -> Result<(), Error> {
+        self._mint_nft_with_traits(to)
+    }
+
+    // Callback function from Supra VRF, called when the trait roll is fulfilled
+    // This is not meant to be called by users
+    pub fn fulfill_traits(&mut self, nonce: U256, rng_list: Vec<U256>) -> Result<(), Error> {
+        self._fulfill_traits(nonce, rng_list)
+    }
+
+    // Reads back the rolled trait values for a token, in trait-slot order.
+    // Uses the trait count recorded at mint time, not the live
+    // `trait_cardinality`, so a later re-tune can't desync past tokens
+    pub fn get_traits(&self, token_id: U256) -> Vec<U256> {
+        let traits = self.token_traits.getter(token_id);
+        let trait_count = self.token_trait_count.get(token_id);
+        (0..trait_count).map(|i| traits.get(i)).collect()
+    }
+
+    // ERC-721-style ownership read: reverts for a token id that was never minted
+    pub fn owner_of(&self, token_id: U256) -> Result<Address, Error> {
+        let owner = self.nft_owner.get(token_id);
+        if owner.is_zero() {
+            return Err(Error::NonexistentToken(NonexistentToken {}));
+        }
+        Ok(owner)
+    }
+
+    // Owner-only: sets the token amount minted to the winner of each round
+    pub fn set_raffle_prize(&mut self, amount: U256) -> Result<(), Error> {
+        self._only_owner()?;
+        self.raffle_prize.set(amount);
+        Ok(())
+    }
+
+    // Enters the caller into the current raffle round
+    pub fn enter_raffle(&mut self) -> Result<(), Error> {
+        self._enter_raffle()
+    }
+
+    // Owner-only: draws a winner for the current round from its entrants
+    pub fn draw_winner(&mut self) -> Result<(), Error> {
+        self._draw_winner()
+    }
+
+    // Callback function from Supra VRF, called when the raffle draw is fulfilled
+    // This is not meant to be called by users
+    pub fn fulfill_raffle(&mut self, nonce: U256, rng_list: Vec<U256>) -> Result<(), Error> {
+        self._fulfill_raffle(nonce, rng_list)
+    }
+
+    // Owner-only: whitelists this contract with the Supra Deposit Contract so
+    // its VRF callbacks are eligible for gas-sponsored fulfillment
+    pub fn add_self_to_whitelist(&mut self) -> Result<(), Error> {
+        self._only_owner()?;
+
+        let contract_address = self.vm().contract_address();
+        let deposit_contract = ISupraDepositContract::from(self.supra_deposit.get());
+        let result = deposit_contract.add_contract_to_whitelist(&mut *self, contract_address);
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(_) => Err(Error::SupraCallFailed(SupraCallFailed {})),
+        }
+    }
+
+    // Owner-only: pre-pays the Supra Deposit Contract so this contract's VRF
+    // callbacks have gas sponsored
+    #[payable]
+    pub fn top_up_deposit(&mut self) -> Result<(), Error> {
+        self._only_owner()?;
+
+        let value = self.vm().msg_value();
+        let deposit_contract = ISupraDepositContract::from(self.supra_deposit.get());
+        let result = deposit_contract.deposit_fund_client(Call::new_in(self).value(value));
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(_) => Err(Error::SupraCallFailed(SupraCallFailed {})),
+        }
+    }
+
+    // Reads this contract's current balance in the Supra Deposit Contract
+    pub fn deposit_balance(&mut self) -> Result<U256, Error> {
+        let contract_address = self.vm().contract_address();
+        let deposit_contract = ISupraDepositContract::from(self.supra_deposit.get());
+        let result = deposit_contract.check_client_fund(&mut *self, contract_address);
+
+        match result {
+            Ok(balance) => Ok(balance),
+            Err(_) => Err(Error::SupraCallFailed(SupraCallFailed {})),
+        }
+    }
+
+    // Owner-only: sets the inclusive bounds a single random mint can fall into
+    pub fn set_mint_range(&mut self, min: U256, max: U256) -> Result<(), Error> {
+        self._only_owner()?;
+        // Reject inverted ranges, so a bad call here can never permanently
+        // brick `_mint_random_amount`; the full-width range is accepted
+        Self::_mint_span(min, max)?;
+        self.min_mint.set(min);
+        self.max_mint.set(max);
+        Ok(())
+    }
+
+    // Owner-only: sets the maximum cumulative amount that can ever be minted
+    pub fn set_supply_cap(&mut self, cap: U256) -> Result<(), Error> {
+        self._only_owner()?;
+        self.supply_cap.set(cap);
+        Ok(())
+    }
 }
 
 impl LotteryToken {
@@ -93,64 +341,283 @@ impl LotteryToken {
         rng_token: Address,
         subscription_manager: Address,
         supra_router: Address,
+        supra_deposit: Address,
     ) -> Result<(), Error> {
+        self.owner.set(self.vm().msg_sender());
         self.rng_token.set(rng_token);
         self.subscription_manager.set(subscription_manager);
         self.supra_router.set(supra_router);
+        self.supra_deposit.set(supra_deposit);
+        self.rng_count.set(1);
+        // Preserve the previous fixed 1..=1,000 token range by default, uncapped
+        self.min_mint.set(U256::from(1));
+        self.max_mint.set(U256::from(1000000000000000000000_u128));
+        self.supply_cap.set(U256::MAX);
         Ok(())
     }
 
+    fn _only_owner(&self) -> Result<(), Error> {
+        if self.vm().msg_sender() != self.owner.get() {
+            return Err(Error::OnlyOwner(OnlyOwner {}));
+        }
+        Ok(())
+    }
+
+    // Computes the inclusive `[min, max]` span width, rejecting inverted
+    // bounds. Returns `None` for the full-width range (`min == 0 && max ==
+    // U256::MAX`), whose span is `2^256` and doesn't fit in a `U256` — that
+    // case needs no modulo since every possible random word is already in range
+    fn _mint_span(min: U256, max: U256) -> Result<Option<U256>, Error> {
+        let diff = max
+            .checked_sub(min)
+            .ok_or(Error::InvalidMintRange(InvalidMintRange {}))?;
+
+        Ok(diff.checked_add(U256::from(1)))
+    }
+
     fn _mint_to(&mut self, to: Address) -> Result<(), Error> {
-        let nonce = self._request_randomness()?;
+        let rng_count = self.rng_count.get();
+        let nonce = self._request_randomness(
+            String::from("mintRandomAmount(uint256,uint256[])"),
+            rng_count,
+        )?;
+        self._register_request(nonce, to);
 
-        self.mint_address.setter(nonce).set(to);
+        Ok(())
+    }
 
-        log(self.vm(), MintRequested { nonce, to });
+    fn _mint_to_with_seed(&mut self, to: Address, client_seed: U256) -> Result<(), Error> {
+        let rng_count = self.rng_count.get();
+        let nonce = self._request_randomness_with_seed(
+            String::from("mintRandomAmount(uint256,uint256[])"),
+            rng_count,
+            client_seed,
+        )?;
+        self._register_request(nonce, to);
 
         Ok(())
     }
 
+    // Records a freshly requested nonce as pending and remembers its receiver,
+    // so `_mint_random_amount` can later verify the fulfillment is legitimate
+    fn _register_request(&mut self, nonce: U256, to: Address) {
+        self.mint_address.setter(nonce).set(to);
+        self.request_pending.setter(nonce).set(true);
+
+        log(self.vm(), MintRequested { nonce, to });
+    }
+
     fn _mint_random_amount(&mut self, nonce: U256, rng_list: Vec<U256>) -> Result<(), Error> {
         // If the caller is not the Supra router, return an error
         if self.vm().msg_sender() != self.supra_router.get() {
             return Err(Error::OnlySupraRouter(OnlySupraRouter {}));
         }
 
+        // Reject fulfillments for nonces that were never requested, or that
+        // were already fulfilled, before touching any mint state
+        if !self.request_pending.get(nonce) {
+            return Err(Error::UnknownRequest(UnknownRequest {}));
+        }
+        self.request_pending.setter(nonce).set(false);
+
         let receiver = self.mint_address.get(nonce);
-        let random_num = rng_list[0];
-        // Mint between 1 and 1,000 tokens
-        let mint_range = U256::from(1000000000000000000000_u128);
-        let mint_amount = (random_num % mint_range) + U256::from(1);
+        // Mint within the owner-configured [min_mint, max_mint] range,
+        // independently for each returned word
+        let min_mint = self.min_mint.get();
+        let max_mint = self.max_mint.get();
+        let mint_span = Self::_mint_span(min_mint, max_mint)?;
+
+        for random_num in rng_list {
+            let mint_amount = match mint_span {
+                Some(span) => min_mint + (random_num % span),
+                None => random_num,
+            };
+
+            let supply_cap = self.supply_cap.get();
+            let new_total = match self.total_minted.get().checked_add(mint_amount) {
+                Some(total) if total <= supply_cap => total,
+                _ => return Err(Error::SupplyCapExceeded(SupplyCapExceeded {})),
+            };
+            self.total_minted.set(new_total);
 
+            let rng_token = IErc20::from(self.rng_token.get());
+            let mint_request = rng_token.mint(&mut *self, receiver, mint_amount);
+
+            if mint_request.is_err() {
+                return Err(Error::MintFailed(MintFailed {}));
+            }
+
+            log(
+                self.vm(),
+                Minted {
+                    nonce,
+                    to: receiver,
+                    amount: mint_amount,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    fn _mint_nft_with_traits(&mut self, to: Address) -> Result<(), Error> {
+        let trait_count = self.trait_cardinality.len() as u8;
+        let nonce = self._request_randomness(
+            String::from("fulfillTraits(uint256,uint256[])"),
+            trait_count,
+        )?;
+
+        let token_id = self.next_token_id.get();
+        self.next_token_id.set(token_id + U256::from(1));
+        self.nft_owner.setter(token_id).set(to);
+        self.token_trait_count.setter(token_id).set(trait_count);
+        self.trait_request_token.setter(nonce).set(token_id);
+        self.request_pending.setter(nonce).set(true);
+
+        log(self.vm(), MintRequested { nonce, to });
+        log(
+            self.vm(),
+            Transfer {
+                from: Address::ZERO,
+                to,
+                token_id,
+            },
+        );
+
+        Ok(())
+    }
+
+    fn _fulfill_traits(&mut self, nonce: U256, rng_list: Vec<U256>) -> Result<(), Error> {
+        if self.vm().msg_sender() != self.supra_router.get() {
+            return Err(Error::OnlySupraRouter(OnlySupraRouter {}));
+        }
+
+        if !self.request_pending.get(nonce) {
+            return Err(Error::UnknownRequest(UnknownRequest {}));
+        }
+        self.request_pending.setter(nonce).set(false);
+
+        let token_id = self.trait_request_token.get(nonce);
+        let mut traits = Vec::with_capacity(rng_list.len());
+        for (i, random_num) in rng_list.into_iter().enumerate() {
+            let cardinality = self.trait_cardinality.get(i).unwrap_or(U256::from(1));
+            let trait_value = random_num % cardinality;
+            self.token_traits
+                .setter(token_id)
+                .setter(i as u8)
+                .set(trait_value);
+            traits.push(trait_value);
+        }
+
+        log(self.vm(), TraitsAssigned { token_id, traits });
+
+        Ok(())
+    }
+
+    fn _enter_raffle(&mut self) -> Result<(), Error> {
+        let round = self.round_id.get();
+        let sender = self.vm().msg_sender();
+        self.entrants.setter(round).push(sender);
+
+        Ok(())
+    }
+
+    fn _draw_winner(&mut self) -> Result<(), Error> {
+        self._only_owner()?;
+
+        let round = self.round_id.get();
+        if self.entrants.getter(round).len() == 0 {
+            return Err(Error::NoEntrants(NoEntrants {}));
+        }
+        if self.raffle_draw_pending.get(round) {
+            return Err(Error::DrawInProgress(DrawInProgress {}));
+        }
+
+        let nonce =
+            self._request_randomness(String::from("fulfillRaffle(uint256,uint256[])"), 1)?;
+        self.raffle_request_round.setter(nonce).set(round);
+        self.raffle_draw_pending.setter(round).set(true);
+        self.request_pending.setter(nonce).set(true);
+
+        Ok(())
+    }
+
+    fn _fulfill_raffle(&mut self, nonce: U256, rng_list: Vec<U256>) -> Result<(), Error> {
+        if self.vm().msg_sender() != self.supra_router.get() {
+            return Err(Error::OnlySupraRouter(OnlySupraRouter {}));
+        }
+
+        if !self.request_pending.get(nonce) {
+            return Err(Error::UnknownRequest(UnknownRequest {}));
+        }
+        self.request_pending.setter(nonce).set(false);
+
+        let round = self.raffle_request_round.get(nonce);
+        self.raffle_draw_pending.setter(round).set(false);
+
+        let entrants = self.entrants.getter(round);
+        let winner_index = (rng_list[0] % U256::from(entrants.len() as u64)).to::<u64>() as usize;
+        let winner = entrants.get(winner_index).unwrap();
+
+        let prize = self.raffle_prize.get();
         let rng_token = IErc20::from(self.rng_token.get());
-        let mint_request = rng_token.mint(&mut *self, receiver, mint_amount);
+        let mint_request = rng_token.mint(&mut *self, winner, prize);
 
         if mint_request.is_err() {
             return Err(Error::MintFailed(MintFailed {}));
         }
 
+        self.round_id.set(round + U256::from(1));
+
         log(
             self.vm(),
-            Minted {
-                nonce,
-                to: receiver,
-                amount: mint_amount,
+            WinnerSelected {
+                round_id: round,
+                winner,
+                amount: prize,
             },
         );
 
         Ok(())
     }
 
-    fn _request_randomness(&mut self) -> Result<U256, Error> {
+    // Requests `rng_count` random words, routed back to the given callback
+    // function signature (e.g. `mintRandomAmount` or `fulfillTraits`)
+    fn _request_randomness(&mut self, function_sig: String, rng_count: u8) -> Result<U256, Error> {
         let subscription_manager = self.subscription_manager.get();
         let supra_router_address = self.supra_router.get();
         let router = ISupraRouterContract::from(supra_router_address);
         let request_result = router.generate_request(
             &mut *self,
-            String::from("mintRandomAmount(uint256,uint256[])"),
-            1,
+            function_sig,
+            rng_count,
+            U256::from(1),
+            subscription_manager,
+        );
+
+        match request_result {
+            Ok(nonce) => Ok(nonce),
+            Err(_) => Err(Error::RandomnessRequestFailed(RandomnessRequestFailed {})),
+        }
+    }
+
+    // Same as `_request_randomness`, but mixes in a caller-supplied `client_seed`
+    fn _request_randomness_with_seed(
+        &mut self,
+        function_sig: String,
+        rng_count: u8,
+        client_seed: U256,
+    ) -> Result<U256, Error> {
+        let subscription_manager = self.subscription_manager.get();
+        let supra_router_address = self.supra_router.get();
+        let router = ISupraRouterContract::from(supra_router_address);
+        let request_result = router.generate_request_1(
+            &mut *self,
+            function_sig,
+            rng_count,
             U256::from(1),
             subscription_manager,
+            client_seed,
         );
 
         match request_result {